@@ -2,6 +2,8 @@ use std::io::Cursor;
 use std::error::Error;
 use std::str;
 use bytes::Buf;
+use flate2::{Compress, Decompress, Compression, FlushCompress, FlushDecompress, Status};
+use rand::random;
 
 type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
 
@@ -10,111 +12,439 @@ pub enum Frame {
     Continuation(Option<FragmentedMessage>),
     Text(String),
     Binary(Vec<u8>),
-    Close(StatusCode),
+    Close(CloseReason),
     Ping(Vec<u8>),
     Pong(Vec<u8>),
 }
 
+/// Which side of the connection this crate is encoding/decoding frames for. The wire format
+/// requires the opposite masking convention for each side: a server must reject unmasked
+/// frames and never masks what it sends, a client is the other way around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
 #[derive(Debug, Clone)]
 pub enum StatusCode {
     Normal = 1000,
     ProtocolError = 1002,
     InvalidDataFormat = 1007,
+    MessageTooBig = 1009,
+}
+
+/// Why a connection closed: the numeric close code plus the peer's (possibly empty) reason
+/// text. Carried by `Frame::Close` so callers can log or react to the actual reason instead of
+/// just one of this crate's own `StatusCode`s.
+#[derive(Debug, Clone)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseReason {
+    pub fn new(code: u16, reason: String) -> Self {
+        Self { code, reason }
+    }
+}
+
+impl From<StatusCode> for CloseReason {
+    fn from(status_code: StatusCode) -> Self {
+        CloseReason::new(status_code as u16, String::new())
+    }
+}
+
+/// Upper bounds on frame and (reassembled) message size, enforced by `Frame::parse` to keep a
+/// single frame or a flood of continuation frames from exhausting memory.
+#[derive(Debug, Clone)]
+pub struct FrameConfig {
+    pub max_frame_size: usize,
+    pub max_message_size: usize,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 64 * 1024,
+            max_message_size: 16 * 1024 * 1024,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum FragmentedMessage {
-    Text(Vec<Vec<u8>>),
-    Binary(Vec<Vec<u8>>),
+    Text(Vec<Vec<u8>>, bool, usize),
+    Binary(Vec<Vec<u8>>, bool, usize),
 }
 
 impl FragmentedMessage {
     fn is_empty(&self) -> bool {
         matches!(
             self,
-            FragmentedMessage::Text(messages) | FragmentedMessage::Binary(messages) if messages.is_empty()
+            FragmentedMessage::Text(messages, ..) | FragmentedMessage::Binary(messages, ..) if messages.is_empty()
         )
     }
 
-    fn push(&mut self, message: Vec<u8>) {
+    fn is_compressed(&self) -> bool {
+        matches!(
+            self,
+            FragmentedMessage::Text(_, compressed, _) | FragmentedMessage::Binary(_, compressed, _) if *compressed
+        )
+    }
+
+    // Appends `message` and returns `false` without appending it if doing so would push the
+    // running total past `max_message_size`.
+    fn push(&mut self, message: Vec<u8>, max_message_size: usize) -> bool {
+        match self {
+            FragmentedMessage::Text(messages, _, total_len) |
+            FragmentedMessage::Binary(messages, _, total_len) => {
+                if *total_len + message.len() > max_message_size {
+                    return false;
+                }
+                *total_len += message.len();
+                messages.push(message);
+                true
+            }
+        }
+    }
+
+    // Replaces the accumulated (still compressed) fragments with the single inflated message.
+    // No-op when the message was never RSV1-flagged. Returns `false` without replacing anything
+    // once the inflated size would exceed `max_message_size`.
+    fn decompress(
+        &mut self,
+        max_message_size: usize,
+        role: Role,
+        permessage_deflate: &mut PermessageDeflate,
+    ) -> Result<bool> {
+        if !self.is_compressed() {
+            return Ok(true);
+        }
         match self {
-            FragmentedMessage::Text(messages) |
-            FragmentedMessage::Binary(messages) => messages.push(message),
+            FragmentedMessage::Text(messages, compressed, total_len) |
+            FragmentedMessage::Binary(messages, compressed, total_len) => {
+                let Some(inflated) = permessage_deflate.inflate(&messages.concat(), max_message_size, role)? else {
+                    return Ok(false);
+                };
+                *total_len = inflated.len();
+                *messages = vec![inflated];
+                *compressed = false;
+            }
         }
+        Ok(true)
     }
 
     fn invalid(&self) -> bool {
         matches!(
             self,
-            FragmentedMessage::Text(messages) if String::from_utf8(messages.concat()).is_err()
+            FragmentedMessage::Text(messages, ..) if String::from_utf8(messages.concat()).is_err()
         )
     }
 
-    fn response(&self) -> Vec<u8> {
-        let (message, first_byte) = match self {
-            FragmentedMessage::Text(messages) => (messages.concat(), 0b1000_0001),
-            FragmentedMessage::Binary(messages) => (messages.concat(), 0b1000_0010),
+    fn response(&self, role: Role, permessage_deflate: Option<&mut PermessageDeflate>) -> Vec<u8> {
+        let (message, opcode) = match self {
+            FragmentedMessage::Text(messages, ..) => (messages.concat(), 0x1),
+            FragmentedMessage::Binary(messages, ..) => (messages.concat(), 0x2),
+        };
+        Frame::encode_payload(opcode, &message, role, permessage_deflate)
+    }
+}
+
+/// Per-connection permessage-deflate (RFC 7692) compression context. Negotiated once during
+/// the handshake (see `Request::response` and `HandshakeResponse.extensions`) and then reused
+/// for every frame unless the peer asked for `*_no_context_takeover`, in which case the sliding
+/// window is reset between messages.
+pub struct PermessageDeflate {
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    inflate: Decompress,
+    deflate: Compress,
+}
+
+impl PermessageDeflate {
+    pub fn new(params: &PermessageDeflateParams) -> Self {
+        Self {
+            client_no_context_takeover: params.client_no_context_takeover,
+            server_no_context_takeover: params.server_no_context_takeover,
+            inflate: Decompress::new(false),
+            deflate: Compress::new(Compression::default(), false),
+        }
+    }
+
+    // `role` is *our* role; we're inflating a message the peer sent us, so the context-takeover
+    // flag that applies is the peer's: a client inflates what the server compressed
+    // (`server_no_context_takeover`), a server inflates what the client compressed
+    // (`client_no_context_takeover`).
+    //
+    // DEFLATE block boundaries strip the trailing empty block; RFC 7692 has senders omit it and
+    // receivers add it back before inflating. Inflates in fixed-size chunks rather than one shot
+    // so a small, highly-compressed payload can't expand past `max_size` before we notice —
+    // returns `Ok(None)` the moment that happens instead of finishing the inflate.
+    fn inflate(&mut self, payload: &[u8], max_size: usize, role: Role) -> Result<Option<Vec<u8>>> {
+        let no_context_takeover = match role {
+            Role::Client => self.server_no_context_takeover,
+            Role::Server => self.client_no_context_takeover,
+        };
+
+        let mut input = payload.to_vec();
+        input.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+
+        let start_in = self.inflate.total_in();
+        let mut output = Vec::new();
+        let mut chunk = [0; 8192];
+        loop {
+            let consumed = (self.inflate.total_in() - start_in) as usize;
+            let produced_before = self.inflate.total_out();
+            let status = self.inflate.decompress(&input[consumed..], &mut chunk, FlushDecompress::Sync)?;
+            let produced = (self.inflate.total_out() - produced_before) as usize;
+            output.extend_from_slice(&chunk[..produced]);
+
+            if output.len() > max_size {
+                if no_context_takeover {
+                    self.inflate.reset(false);
+                }
+                return Ok(None);
+            }
+            // A filled chunk may leave decoded bytes pending even after all input is consumed.
+            let all_in = (self.inflate.total_in() - start_in) as usize >= input.len();
+            if status == Status::StreamEnd || (all_in && produced < chunk.len()) {
+                break;
+            }
+        }
+        if no_context_takeover {
+            self.inflate.reset(false);
+        }
+        Ok(Some(output))
+    }
+
+    // `role` is *our* role; we're compressing our own outbound message, so the context-takeover
+    // flag that applies is our own: a client resets on `client_no_context_takeover`, a server on
+    // `server_no_context_takeover`.
+    fn deflate(&mut self, payload: &[u8], role: Role) -> Vec<u8> {
+        let no_context_takeover = match role {
+            Role::Client => self.client_no_context_takeover,
+            Role::Server => self.server_no_context_takeover,
         };
-        let mut response = vec![first_byte];
-        response.extend(Frame::payload_length_response(message.len()));
-        response.extend(message);
-        response
+
+        // `compress_vec` only writes into spare capacity, so grow the buffer until all input is
+        // consumed and the sync flush finished with room to spare.
+        let start_in = self.deflate.total_in();
+        let mut output = Vec::with_capacity(payload.len() + 64);
+        loop {
+            let consumed = (self.deflate.total_in() - start_in) as usize;
+            self.deflate
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .expect("in-memory deflate cannot fail");
+            let all_in = (self.deflate.total_in() - start_in) as usize >= payload.len();
+            if all_in && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity().max(64));
+        }
+        output.truncate(output.len().saturating_sub(4));
+        if no_context_takeover {
+            self.deflate.reset();
+        }
+        output
     }
 }
 
+/// Negotiated `permessage-deflate` parameters, as offered by the client and accepted by
+/// `Request::response`.
+#[derive(Debug, Clone, Default)]
+pub struct PermessageDeflateParams {
+    pub client_no_context_takeover: bool,
+    pub server_no_context_takeover: bool,
+    pub server_max_window_bits: Option<u8>,
+}
+
+enum HeaderPeek {
+    Incomplete,
+    TooBig { header_len: usize },
+    Ready { frame_len: usize },
+}
+
 impl Frame {
+    /// Parses one frame out of `src`. Returns `Ok(None)` when `src` doesn't yet hold a full
+    /// frame, leaving its position unadvanced so the caller can append more bytes from the
+    /// socket and retry with the same (now longer) buffer.
     pub fn parse(
         src: &mut Cursor<&[u8]>,
         fragmented_message: &mut FragmentedMessage,
+        config: &FrameConfig,
+        role: Role,
+        permessage_deflate: Option<&mut PermessageDeflate>,
+    ) -> Result<Option<Frame>> {
+        let start = src.position() as usize;
+        let peek = Frame::peek_header(&src.get_ref()[start..], config.max_frame_size);
+        let available = src.get_ref().len() - start;
+
+        match peek {
+            HeaderPeek::Incomplete => Ok(None),
+            HeaderPeek::TooBig { header_len } => {
+                if available < header_len {
+                    return Ok(None);
+                }
+                src.advance(header_len);
+                Ok(Some(Frame::Close(StatusCode::MessageTooBig.into())))
+            }
+            HeaderPeek::Ready { frame_len } => {
+                if available < frame_len {
+                    return Ok(None);
+                }
+                Frame::parse_complete(src, fragmented_message, config, role, permessage_deflate).map(Some)
+            }
+        }
+    }
+
+    // Looks at a frame header without consuming it, reporting either that more bytes are
+    // needed, that the declared payload already exceeds `max_frame_size`, or the total byte
+    // count (header + payload) the caller must have buffered before `parse_complete` can run.
+    fn peek_header(buf: &[u8], max_frame_size: usize) -> HeaderPeek {
+        if buf.len() < 2 {
+            return HeaderPeek::Incomplete;
+        }
+        let mask_byte = buf[1];
+        let masked = mask_byte & 0b1000_0000 != 0;
+        let base_len = (mask_byte & 0b0111_1111) as usize;
+        let (ext_len, payload_length) = match base_len {
+            0..=125 => (0, base_len),
+            126 => {
+                if buf.len() < 4 {
+                    return HeaderPeek::Incomplete;
+                }
+                (2, u16::from_be_bytes([buf[2], buf[3]]) as usize)
+            }
+            127 => {
+                if buf.len() < 10 {
+                    return HeaderPeek::Incomplete;
+                }
+                (8, u64::from_be_bytes(buf[2..10].try_into().unwrap()) as usize)
+            }
+            _ => unreachable!("base_len comes from 7 bits"),
+        };
+        let header_len = 2 + ext_len + if masked { 4 } else { 0 };
+        if payload_length > max_frame_size {
+            return HeaderPeek::TooBig { header_len };
+        }
+        HeaderPeek::Ready { frame_len: header_len + payload_length }
+    }
+
+    // Runs once `parse` has confirmed the full frame is buffered, so none of the field
+    // readers below can fail for lack of data.
+    fn parse_complete(
+        src: &mut Cursor<&[u8]>,
+        fragmented_message: &mut FragmentedMessage,
+        config: &FrameConfig,
+        role: Role,
+        mut permessage_deflate: Option<&mut PermessageDeflate>,
     ) -> Result<Frame> {
         let f_byte = get_u8(src)?;
         let fin = f_byte & 0b1000_0000 != 0;
-        let rsv = f_byte & 0b0111_0000 != 0;
+        let rsv1 = f_byte & 0b0100_0000 != 0;
+        let rsv2_3 = f_byte & 0b0011_0000 != 0;
         let opcode = f_byte & 0b0000_1111;
 
         let s_byte = get_u8(src)?;
         let mask = s_byte & 0b1000_0000 != 0;
         let mut payload_length = (s_byte & 0b0111_1111) as usize;
 
-        if rsv || !mask {
-            return Ok(Frame::Close(StatusCode::ProtocolError));
+        // RSV1 only carries meaning on the first frame of a data message (RFC 7692 §5.2
+        // forbids it on continuation and control frames alike) and only once
+        // permessage-deflate has been negotiated.
+        let rsv1_allowed = rsv1 && permessage_deflate.is_some() && (opcode == 0x1 || opcode == 0x2);
+        // A server only ever receives masked frames (from a client); a client only ever
+        // receives unmasked frames (from a server).
+        let mask_expected = role == Role::Server;
+        if rsv2_3 || (rsv1 && !rsv1_allowed) || mask != mask_expected {
+            return Ok(Frame::Close(StatusCode::ProtocolError.into()));
         }
 
         payload_length = Frame::payload_length(src, payload_length)?;
-        let mask = Frame::mask(src)?;
-        let message = Frame::decoded_message(src, payload_length, &mask)?;
+        let mask = if mask { Some(Frame::mask(src)?) } else { None };
+        let message = Frame::decoded_message(src, payload_length, mask.as_ref())?;
 
         if !fin {
             match opcode {
-                0x0 if fragmented_message.is_empty() => return Ok(Frame::Close(StatusCode::ProtocolError)),
-                0x0 | 0x1 => fragmented_message.push(message),
+                0x0 if fragmented_message.is_empty() => return Ok(Frame::Close(StatusCode::ProtocolError.into())),
+                0x0 => {
+                    if !fragmented_message.push(message, config.max_message_size) {
+                        return Ok(Frame::Close(StatusCode::MessageTooBig.into()));
+                    }
+                }
+                0x1 => {
+                    *fragmented_message = FragmentedMessage::Text(Vec::new(), rsv1, 0);
+                    if !fragmented_message.push(message, config.max_message_size) {
+                        return Ok(Frame::Close(StatusCode::MessageTooBig.into()));
+                    }
+                }
                 0x2 => {
-                    *fragmented_message = FragmentedMessage::Binary(Vec::new());
-                    fragmented_message.push(message)
+                    *fragmented_message = FragmentedMessage::Binary(Vec::new(), rsv1, 0);
+                    if !fragmented_message.push(message, config.max_message_size) {
+                        return Ok(Frame::Close(StatusCode::MessageTooBig.into()));
+                    }
                 }
-                _ => return Ok(Frame::Close(StatusCode::ProtocolError)),
+                _ => return Ok(Frame::Close(StatusCode::ProtocolError.into())),
             }
             return Ok(Frame::Continuation(None));
         }
 
         match opcode {
-            0x0 if fragmented_message.is_empty() => Ok(Frame::Close(StatusCode::ProtocolError)),
-            0x0 if fragmented_message.invalid() => Ok(Frame::Close(StatusCode::InvalidDataFormat)),
+            0x0 if fragmented_message.is_empty() => Ok(Frame::Close(StatusCode::ProtocolError.into())),
             0x0 => {
-                fragmented_message.push(message);
-                Ok(Frame::Continuation(Some(fragmented_message.clone())))
+                if !fragmented_message.push(message, config.max_message_size) {
+                    return Ok(Frame::Close(StatusCode::MessageTooBig.into()));
+                }
+                if let Some(permessage_deflate) = permessage_deflate.as_deref_mut() {
+                    if !fragmented_message.decompress(config.max_message_size, role, permessage_deflate)? {
+                        return Ok(Frame::Close(StatusCode::MessageTooBig.into()));
+                    }
+                }
+                if fragmented_message.invalid() {
+                    return Ok(Frame::Close(StatusCode::InvalidDataFormat.into()));
+                }
+                let message = fragmented_message.clone();
+                *fragmented_message = FragmentedMessage::Text(Vec::new(), false, 0);
+                Ok(Frame::Continuation(Some(message)))
             }
-            0x1 | 0x2 if !fragmented_message.is_empty() => Ok(Frame::Close(StatusCode::ProtocolError)),
-            0x1 => match String::from_utf8(message) {
-                Ok(message) => Ok(Frame::Text(message)),
-                Err(_) => Ok(Frame::Close(StatusCode::InvalidDataFormat))
+            0x1 | 0x2 if !fragmented_message.is_empty() => Ok(Frame::Close(StatusCode::ProtocolError.into())),
+            0x1 => {
+                match Frame::inflate_if_needed(message, rsv1, config.max_message_size, role, permessage_deflate)? {
+                    None => Ok(Frame::Close(StatusCode::MessageTooBig.into())),
+                    Some(message) => match String::from_utf8(message) {
+                        Ok(message) => Ok(Frame::Text(message)),
+                        Err(_) => Ok(Frame::Close(StatusCode::InvalidDataFormat.into()))
+                    }
+                }
             }
-            0x2 => Ok(Frame::Binary(message)),
-            0x8 if payload_length == 0 => Ok(Frame::Close(StatusCode::Normal)),
+            0x2 => match Frame::inflate_if_needed(message, rsv1, config.max_message_size, role, permessage_deflate)? {
+                None => Ok(Frame::Close(StatusCode::MessageTooBig.into())),
+                Some(message) => Ok(Frame::Binary(message)),
+            },
+            0x8 if payload_length == 0 => Ok(Frame::Close(StatusCode::Normal.into())),
             0x8 if (2..=125).contains(&payload_length) => Frame::parse_close_frame(message),
             0x9 if (0..=125).contains(&payload_length) => Ok(Frame::Ping(message)),
             0xA => Ok(Frame::Pong(message)),
-            _ => Ok(Frame::Close(StatusCode::ProtocolError)),
+            _ => Ok(Frame::Close(StatusCode::ProtocolError.into())),
+        }
+    }
+
+    // Returns `Ok(None)` once the inflated size would exceed `max_message_size`, which callers
+    // turn into a `Frame::Close(StatusCode::MessageTooBig)` rather than a hard error.
+    fn inflate_if_needed(
+        message: Vec<u8>,
+        rsv1: bool,
+        max_message_size: usize,
+        role: Role,
+        permessage_deflate: Option<&mut PermessageDeflate>,
+    ) -> Result<Option<Vec<u8>>> {
+        if !rsv1 {
+            return Ok(Some(message));
+        }
+        match permessage_deflate {
+            Some(permessage_deflate) => permessage_deflate.inflate(&message, max_message_size, role),
+            None => Err("Received RSV1 without a negotiated extension".into()),
         }
     }
 
@@ -139,61 +469,94 @@ impl Frame {
     fn decoded_message(
         src: &mut Cursor<&[u8]>,
         payload_length: usize,
-        mask: &[u8; 4]
+        mask: Option<&[u8; 4]>
     ) -> Result<Vec<u8>> {
         if src.remaining() < payload_length {
             return Err("Cannot decode message".into())
         }
-        let mut encoded = vec![0; payload_length];
-        src.copy_to_slice(&mut encoded);
-        Ok(encoded
-            .iter()
-            .enumerate()
-            .map(|(i, val)| val ^ mask[i % 4])
-            .collect()
-        )
+        let mut message = vec![0; payload_length];
+        src.copy_to_slice(&mut message);
+        if let Some(mask) = mask {
+            for (i, byte) in message.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        Ok(message)
     }
 
     fn parse_close_frame(message: Vec<u8>) -> Result<Frame> {
         let valid = [1000, 1001, 1002, 1003, 1007, 1008, 1009,
             1010, 1011, 3000, 3999, 4000, 4999];
-        let status_code = (&message[0..2]).get_u16();
-        if !valid.contains(&status_code) {
-            return Ok(Frame::Close(StatusCode::ProtocolError));
+        let code = (&message[0..2]).get_u16();
+        if !valid.contains(&code) {
+            return Ok(Frame::Close(StatusCode::ProtocolError.into()));
         }
-        match str::from_utf8(&message[2..]) {
-            Ok(_) => Ok(Frame::Close(StatusCode::Normal)),
-            Err(_) => Ok(Frame::Close(StatusCode::ProtocolError))
+        match String::from_utf8(message[2..].to_vec()) {
+            Ok(reason) => Ok(Frame::Close(CloseReason::new(code, reason))),
+            Err(_) => Ok(Frame::Close(StatusCode::ProtocolError.into()))
         }
     }
 
-    pub fn response(&self) -> Option<Vec<u8>> {
-        let mut response = Vec::new();
+    pub fn response(&self, role: Role, permessage_deflate: Option<&mut PermessageDeflate>) -> Option<Vec<u8>> {
         match self {
-            Frame::Continuation(Some(message)) => response.extend(message.response()),
-            Frame::Text(message) => {
-                response.push(0b1000_0001);
-                let payload = message.as_bytes();
-                response.extend(Frame::payload_length_response(payload.len()));
-                response.extend(payload);
-            }
-            Frame::Binary(message) => {
-                response.push(0b1000_0010);
-                response.extend(Frame::payload_length_response(message.len()));
-                response.extend(message);
-            }
-            Frame::Close(status_code) => {
-                response.extend([0b1000_1000, 0b0000_0010]);
-                response.extend((status_code.clone() as u16).to_be_bytes());
+            Frame::Continuation(Some(message)) => Some(message.response(role, permessage_deflate)),
+            Frame::Text(message) => Some(Frame::encode_payload(0x1, message.as_bytes(), role, permessage_deflate)),
+            Frame::Binary(message) => Some(Frame::encode_payload(0x2, message, role, permessage_deflate)),
+            Frame::Close(close_reason) => Some(Frame::encode_frame(0x8, false, Frame::close_payload(close_reason), role)),
+            Frame::Ping(message) => Some(Frame::encode_frame(0x9, false, message.clone(), role)),
+            Frame::Pong(message) => Some(Frame::encode_frame(0xA, false, message.clone(), role)),
+            Frame::Continuation(None) => None,
+        }
+    }
+
+    // A close frame's payload is the 2-byte code followed by the UTF-8 reason, capped at the
+    // 125-byte control-frame payload limit; the reason is truncated on a char boundary.
+    fn close_payload(close_reason: &CloseReason) -> Vec<u8> {
+        const MAX_REASON_LEN: usize = 125 - 2;
+        let mut reason_len = close_reason.reason.len().min(MAX_REASON_LEN);
+        while reason_len > 0 && !close_reason.reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        let mut payload = close_reason.code.to_be_bytes().to_vec();
+        payload.extend(close_reason.reason[..reason_len].as_bytes());
+        payload
+    }
+
+    // Shared by Text/Binary responses: deflates the payload and sets RSV1 when a compression
+    // context was negotiated, otherwise sends the payload as-is.
+    fn encode_payload(
+        opcode: u8,
+        payload: &[u8],
+        role: Role,
+        permessage_deflate: Option<&mut PermessageDeflate>,
+    ) -> Vec<u8> {
+        let (payload, rsv1) = match permessage_deflate {
+            Some(permessage_deflate) => (permessage_deflate.deflate(payload, role), true),
+            None => (payload.to_vec(), false),
+        };
+        Frame::encode_frame(opcode, rsv1, payload, role)
+    }
+
+    // Builds one complete outbound frame. In `Role::Client` the mask bit is set, a fresh
+    // masking key is generated per frame, and the payload is XORed with it; a server never
+    // masks what it sends.
+    fn encode_frame(opcode: u8, rsv1: bool, payload: Vec<u8>, role: Role) -> Vec<u8> {
+        let mut frame = vec![0b1000_0000 | opcode | if rsv1 { 0b0100_0000 } else { 0 }];
+        let mut length = Frame::payload_length_response(payload.len());
+        match role {
+            Role::Server => {
+                frame.extend(length);
+                frame.extend(payload);
             }
-            Frame::Ping(message) => {
-                response.push(0b1000_1010);
-                response.push(message.len() as u8);
-                response.extend(message);
+            Role::Client => {
+                length[0] |= 0b1000_0000;
+                let mask: [u8; 4] = random();
+                frame.extend(length);
+                frame.extend(mask);
+                frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
             }
-            _ => {}
         }
-        if !response.is_empty() { Some(response) } else { None }
+        frame
     }
 
     fn payload_length_response(payload_length: usize) -> Vec<u8> {
@@ -258,4 +621,201 @@ impl VecExt for Vec<u8> {
     fn is_close(&self) -> bool {
         self.first().map_or(false, |&byte| byte == 0b1000_1000)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_text_fragment() -> FragmentedMessage {
+        FragmentedMessage::Text(Vec::new(), false, 0)
+    }
+
+    // `Frame::parse`'s entire contract is resuming across partial reads: `Ok(None)` with the
+    // cursor left unadvanced until the buffer holds a full frame, then `Ok(Some(..))` once it
+    // does. Feeding the frame one byte at a time is the most direct way to pin that down.
+    #[test]
+    fn parse_resumes_across_partial_reads() {
+        let config = FrameConfig::default();
+        let frame_bytes = [0x81, 0x02, b'h', b'i'];
+
+        for len in 0..frame_bytes.len() {
+            let mut fragmented_message = empty_text_fragment();
+            let mut src = Cursor::new(&frame_bytes[..len]);
+            let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+            assert!(frame.is_none(), "expected Ok(None) at {len} of {} bytes", frame_bytes.len());
+            assert_eq!(src.position(), 0, "cursor must stay unadvanced while a frame is incomplete");
+        }
+
+        let mut fragmented_message = empty_text_fragment();
+        let mut src = Cursor::new(&frame_bytes[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        match frame {
+            Some(Frame::Text(message)) => assert_eq!(message, "hi"),
+            other => panic!("expected a complete Text frame, got {other:?}"),
+        }
+        assert_eq!(src.position() as usize, frame_bytes.len());
+    }
+
+    #[test]
+    fn parse_rejects_frames_over_max_frame_size() {
+        let config = FrameConfig { max_frame_size: 4, max_message_size: 1024 };
+        // Header alone (fin=1, binary, unmasked) advertises a 16-byte payload, over `max_frame_size`.
+        let frame_bytes = [0x82, 16];
+        let mut fragmented_message = empty_text_fragment();
+        let mut src = Cursor::new(&frame_bytes[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        match frame {
+            Some(Frame::Close(reason)) => assert_eq!(reason.code, StatusCode::MessageTooBig as u16),
+            other => panic!("expected a MessageTooBig close, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_rejects_fragmented_messages_over_max_message_size() {
+        let config = FrameConfig { max_frame_size: 1024, max_message_size: 4 };
+        let mut fragmented_message = empty_text_fragment();
+
+        // First fragment: fin=0, text, 3 bytes — under the limit on its own.
+        let first = [0x01, 0x03, b'a', b'b', b'c'];
+        let mut src = Cursor::new(&first[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        assert!(matches!(frame, Some(Frame::Continuation(None))));
+
+        // Second fragment: fin=1, continuation, 3 more bytes — pushes the running total to 6.
+        let second = [0x80, 0x03, b'd', b'e', b'f'];
+        let mut src = Cursor::new(&second[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        match frame {
+            Some(Frame::Close(reason)) => assert_eq!(reason.code, StatusCode::MessageTooBig as u16),
+            other => panic!("expected a MessageTooBig close, got {other:?}"),
+        }
+    }
+
+    // A completed fragmented message must clear `fragmented_message` so the next, ordinary
+    // single-frame message isn't mistaken for an unexpected continuation.
+    #[test]
+    fn parse_accepts_single_frame_message_after_a_completed_fragmented_message() {
+        let config = FrameConfig::default();
+        let mut fragmented_message = empty_text_fragment();
+
+        // "he" + "llo", fragmented across two frames.
+        let first = [0x01, 0x02, b'h', b'e'];
+        let mut src = Cursor::new(&first[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        assert!(matches!(frame, Some(Frame::Continuation(None))));
+
+        let second = [0x80, 0x03, b'l', b'l', b'o'];
+        let mut src = Cursor::new(&second[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        match frame {
+            Some(Frame::Continuation(Some(message))) => match message {
+                FragmentedMessage::Text(messages, ..) => assert_eq!(messages.concat(), b"hello"),
+                other => panic!("expected a Text fragment, got {other:?}"),
+            },
+            other => panic!("expected a complete Continuation, got {other:?}"),
+        }
+
+        // A plain, unfragmented "hi" on the same connection must not be rejected as an
+        // unexpected continuation.
+        let third = [0x81, 0x02, b'h', b'i'];
+        let mut src = Cursor::new(&third[..]);
+        let frame = Frame::parse(&mut src, &mut fragmented_message, &config, Role::Client, None).unwrap();
+        match frame {
+            Some(Frame::Text(message)) => assert_eq!(message, "hi"),
+            other => panic!("expected a complete Text frame, got {other:?}"),
+        }
+    }
+
+    // RFC 7692 §5.2: RSV1 only carries meaning on the first frame of a data message, never on a
+    // control frame. A Ping with RSV1 set must be rejected rather than handed to the app with its
+    // still-compressed payload.
+    #[test]
+    fn parse_rejects_rsv1_on_control_frames() {
+        let config = FrameConfig::default();
+        let mut fragmented_message = empty_text_fragment();
+        let mut permessage_deflate = PermessageDeflate::new(&PermessageDeflateParams::default());
+
+        // fin=1, rsv1=1, opcode=0x9 (Ping), unmasked, empty payload.
+        let frame_bytes = [0b1100_1001, 0x00];
+        let mut src = Cursor::new(&frame_bytes[..]);
+        let frame = Frame::parse(
+            &mut src,
+            &mut fragmented_message,
+            &config,
+            Role::Client,
+            Some(&mut permessage_deflate),
+        ).unwrap();
+        match frame {
+            Some(Frame::Close(reason)) => assert_eq!(reason.code, StatusCode::ProtocolError as u16),
+            other => panic!("expected a ProtocolError close, got {other:?}"),
+        }
+    }
+
+    // `PermessageDeflate::inflate` must not allocate past `max_message_size` just because the
+    // compressed wire bytes were small.
+    #[test]
+    fn inflate_caps_output_at_max_message_size() {
+        let params = PermessageDeflateParams::default();
+        let payload = vec![b'a'; 4096];
+        let compressed = PermessageDeflate::new(&params).deflate(&payload, Role::Server);
+
+        let mut decompressor = PermessageDeflate::new(&params);
+        let inflated = decompressor.inflate(&compressed, 1024, Role::Client).unwrap();
+        assert!(inflated.is_none(), "expected inflate to report the output as over max_size");
+    }
+
+    // `deflate`'s reset decision is governed by *our own* role: a client resets on
+    // `client_no_context_takeover`, a server on `server_no_context_takeover`. With only the
+    // client flag set, two consecutive client-compressed messages can't share a sliding window
+    // (so they compress to the same size), while two consecutive server-compressed messages can.
+    #[test]
+    fn deflate_resets_based_on_own_role() {
+        let mut params = PermessageDeflateParams::default();
+        params.client_no_context_takeover = true;
+        let payload = b"the quick brown fox jumps over the lazy dog ".repeat(4);
+
+        let mut client_side = PermessageDeflate::new(&params);
+        let first = client_side.deflate(&payload, Role::Client);
+        let second = client_side.deflate(&payload, Role::Client);
+        assert_eq!(first.len(), second.len(), "client role must reset its own context between messages");
+
+        let mut server_side = PermessageDeflate::new(&params);
+        let first = server_side.deflate(&payload, Role::Server);
+        let second = server_side.deflate(&payload, Role::Server);
+        assert!(second.len() < first.len(), "server role must retain its context across messages");
+    }
+
+    // `inflate`'s reset decision is governed by the *peer's* role: a server inflating client
+    // messages resets on `client_no_context_takeover`, a client inflating server messages resets
+    // on `server_no_context_takeover`. Compress two messages back-to-back with a retained
+    // context, then confirm only the role that maps to the matching (non-reset) flag can
+    // actually decompress the second one.
+    #[test]
+    fn inflate_resets_based_on_peer_role() {
+        let mut params = PermessageDeflateParams::default();
+        params.server_no_context_takeover = true;
+        let payload = b"the quick brown fox jumps over the lazy dog ".repeat(4);
+
+        // client_no_context_takeover is false, so a client retains its compression context
+        // across messages; the second message's back-references rely on the first's window.
+        let mut compressor = PermessageDeflate::new(&params);
+        let first_compressed = compressor.deflate(&payload, Role::Client);
+        let second_compressed = compressor.deflate(&payload, Role::Client);
+
+        // A server inflating client messages maps to `client_no_context_takeover` (false here),
+        // so it must also retain context and decode the second message correctly.
+        let mut correct_decompressor = PermessageDeflate::new(&params);
+        correct_decompressor.inflate(&first_compressed, payload.len() * 2, Role::Server).unwrap();
+        let second = correct_decompressor.inflate(&second_compressed, payload.len() * 2, Role::Server).unwrap();
+        assert_eq!(second, Some(payload.clone()));
+
+        // A client inflating these same messages would (incorrectly, if the bug reappeared) map
+        // to `server_no_context_takeover` (true here), resetting its window between messages and
+        // failing to decode the second message's back-references into the first.
+        let mut wrong_decompressor = PermessageDeflate::new(&params);
+        wrong_decompressor.inflate(&first_compressed, payload.len() * 2, Role::Client).unwrap();
+        let result = wrong_decompressor.inflate(&second_compressed, payload.len() * 2, Role::Client);
+        assert!(result.is_err(), "resetting between messages should fail to decode the shared-window back-references");
+    }
+}
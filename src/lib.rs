@@ -1,9 +1,16 @@
 pub mod frame;
+pub mod message;
 pub mod request;
-mod frame;
 
+pub use frame::CloseReason;
 pub use frame::Frame;
+pub use frame::FrameConfig;
 pub use frame::FragmentedMessage;
+pub use frame::PermessageDeflate;
+pub use frame::PermessageDeflateParams;
+pub use frame::Role;
 pub use frame::StatusCode;
 pub use frame::VecExt;
+pub use message::Message;
+pub use request::HandshakeResponse;
 pub use request::Request;
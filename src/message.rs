@@ -0,0 +1,27 @@
+use crate::frame::{CloseReason, Frame, PermessageDeflate, Role};
+
+/// A role-agnostic WebSocket message. Encodes straight to wire bytes, so the same frame
+/// machinery in `frame` can power both a client and a server without callers building a
+/// `Frame` by hand.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(CloseReason),
+}
+
+impl Message {
+    pub fn encode(&self, role: Role, permessage_deflate: Option<&mut PermessageDeflate>) -> Vec<u8> {
+        let frame = match self {
+            Message::Text(message) => Frame::Text(message.clone()),
+            Message::Binary(message) => Frame::Binary(message.clone()),
+            Message::Ping(message) => Frame::Ping(message.clone()),
+            Message::Pong(message) => Frame::Pong(message.clone()),
+            Message::Close(close_reason) => Frame::Close(close_reason.clone()),
+        };
+        frame.response(role, permessage_deflate)
+            .expect("every Message variant encodes to a non-empty frame")
+    }
+}
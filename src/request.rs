@@ -4,11 +4,21 @@ use std::str::Utf8Error;
 use sha1::{Sha1, Digest};
 use base64::prelude::*;
 use base64::prelude::BASE64_STANDARD;
+use crate::frame::PermessageDeflateParams;
 
 pub struct Request<'a> {
     data: &'a str,
 }
 
+/// The result of handling a handshake request: the raw HTTP response to write back, plus
+/// whatever got negotiated along the way. `protocol`/`extensions` are `None` whenever the
+/// handshake was rejected (`response` is then a 400/426, not a 101).
+pub struct HandshakeResponse {
+    pub response: String,
+    pub protocol: Option<String>,
+    pub extensions: Option<PermessageDeflateParams>,
+}
+
 impl<'a> Request<'a> {
     pub fn new(data: &'a [u8]) -> Result<Self, Utf8Error> {
         str::from_utf8(data)
@@ -36,16 +46,197 @@ impl<'a> Request<'a> {
         BASE64_STANDARD.encode(hash)
     }
 
-    pub fn response(&self) -> Option<String> {
-        let headers = self.parse_headers();
-        headers.get("sec-websocket-key").map(|key| {
-            let accept_key = self.websocket_accept_key(key);
-            format!(
-                "HTTP/1.1 101 Switching Protocols\r\n\
-                Upgrade: websocket\r\n\
-                Connection: Upgrade\r\n\
-                Sec-WebSocket-Accept: {accept_key}\r\n\r\n"
-            )
+    // Rejects anything that isn't a well-formed RFC 6455 upgrade request: wrong/missing
+    // `Sec-WebSocket-Version`, or a missing `Upgrade: websocket` / `Connection: Upgrade` token.
+    fn validation_error(&self, headers: &HashMap<String, String>) -> Option<String> {
+        let version_supported = headers
+            .get("sec-websocket-version")
+            .is_some_and(|version| version.trim() == "13");
+        if !version_supported {
+            return Some(Self::upgrade_required());
+        }
+
+        let has_token = |header: &str, token: &str| {
+            headers
+                .get(header)
+                .is_some_and(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)))
+        };
+        if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+            return Some(Self::bad_request());
+        }
+
+        None
+    }
+
+    fn bad_request() -> String {
+        "HTTP/1.1 400 Bad Request\r\n\r\n".to_string()
+    }
+
+    fn upgrade_required() -> String {
+        "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n\r\n".to_string()
+    }
+
+    // Picks the first client-offered subprotocol (in the client's preference order) that the
+    // server was configured to support.
+    fn negotiate_protocol(&self, headers: &HashMap<String, String>, supported_protocols: &[&str]) -> Option<String> {
+        let offer = headers.get("sec-websocket-protocol")?;
+        offer.split(',')
+            .map(str::trim)
+            .find(|protocol| supported_protocols.contains(protocol))
+            .map(String::from)
+    }
+
+    // Parses the `permessage-deflate` offer out of `Sec-WebSocket-Extensions`, if present.
+    // Unknown extensions and unknown parameters within a known extension are ignored.
+    fn parse_extensions(&self, headers: &HashMap<String, String>) -> Option<PermessageDeflateParams> {
+        let offer = headers.get("sec-websocket-extensions")?;
+        offer.split(',').find_map(|extension| {
+            let mut parts = extension.split(';').map(str::trim);
+            if parts.next()? != "permessage-deflate" {
+                return None;
+            }
+            let mut params = PermessageDeflateParams::default();
+            for part in parts {
+                let mut key_value = part.splitn(2, '=');
+                match key_value.next()?.trim() {
+                    "client_no_context_takeover" => params.client_no_context_takeover = true,
+                    "server_no_context_takeover" => params.server_no_context_takeover = true,
+                    "server_max_window_bits" => {
+                        params.server_max_window_bits = key_value.next().and_then(|bits| bits.trim().parse().ok());
+                    }
+                    _ => {}
+                }
+            }
+            Some(params)
         })
     }
-}
\ No newline at end of file
+
+    fn permessage_deflate_header(params: &PermessageDeflateParams) -> String {
+        let mut header = String::from("Sec-WebSocket-Extensions: permessage-deflate");
+        if params.client_no_context_takeover {
+            header.push_str("; client_no_context_takeover");
+        }
+        if params.server_no_context_takeover {
+            header.push_str("; server_no_context_takeover");
+        }
+        if let Some(bits) = params.server_max_window_bits {
+            header.push_str(&format!("; server_max_window_bits={bits}"));
+        }
+        header.push_str("\r\n");
+        header
+    }
+
+    /// Validates the handshake and, once accepted, negotiates a subprotocol (from
+    /// `supported_protocols`, tried in the client's offered order) and any extensions. The
+    /// negotiated protocol/extensions are echoed in the response and also handed back so the
+    /// caller knows what was agreed for this connection.
+    pub fn response(&self, supported_protocols: &[&str]) -> HandshakeResponse {
+        let headers = self.parse_headers();
+
+        if let Some(response) = self.validation_error(&headers) {
+            return HandshakeResponse { response, protocol: None, extensions: None };
+        }
+
+        let Some(key) = headers.get("sec-websocket-key") else {
+            return HandshakeResponse { response: Self::bad_request(), protocol: None, extensions: None };
+        };
+
+        let accept_key = self.websocket_accept_key(key);
+        let protocol = self.negotiate_protocol(&headers, supported_protocols);
+        let extensions = self.parse_extensions(&headers);
+
+        let mut response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {accept_key}\r\n"
+        );
+        if let Some(protocol) = &protocol {
+            response.push_str(&format!("Sec-WebSocket-Protocol: {protocol}\r\n"));
+        }
+        if let Some(extensions) = &extensions {
+            response.push_str(&Self::permessage_deflate_header(extensions));
+        }
+        response.push_str("\r\n");
+
+        HandshakeResponse { response, protocol, extensions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(extra_headers: &str) -> String {
+        format!(
+            "GET /chat HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            {extra_headers}\r\n\r\n"
+        )
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_websocket_version() {
+        let data = "GET /chat HTTP/1.1\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 12\r\n\r\n";
+        let response = Request::new(data.as_bytes()).unwrap().response(&[]);
+        assert!(response.response.starts_with("HTTP/1.1 426 Upgrade Required"));
+        assert!(response.protocol.is_none());
+        assert!(response.extensions.is_none());
+
+        let data = "GET /chat HTTP/1.1\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let response = Request::new(data.as_bytes()).unwrap().response(&[]);
+        assert!(response.response.starts_with("HTTP/1.1 426 Upgrade Required"));
+    }
+
+    #[test]
+    fn rejects_missing_upgrade_or_connection_tokens() {
+        let data = "GET /chat HTTP/1.1\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let response = Request::new(data.as_bytes()).unwrap().response(&[]);
+        assert!(response.response.starts_with("HTTP/1.1 400 Bad Request"));
+
+        let data = "GET /chat HTTP/1.1\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let response = Request::new(data.as_bytes()).unwrap().response(&[]);
+        assert!(response.response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn negotiates_first_supported_subprotocol_in_client_preference_order() {
+        let data = handshake("Sec-WebSocket-Protocol: foo, bar\r\n");
+        let response = Request::new(data.as_bytes()).unwrap().response(&["bar", "foo"]);
+        assert_eq!(response.protocol, Some("foo".to_string()));
+        assert!(response.response.contains("Sec-WebSocket-Protocol: foo\r\n"));
+    }
+
+    #[test]
+    fn echoes_accepted_permessage_deflate_extension() {
+        let data = handshake(
+            "Sec-WebSocket-Extensions: permessage-deflate; client_no_context_takeover; server_max_window_bits=10\r\n"
+        );
+        let response = Request::new(data.as_bytes()).unwrap().response(&[]);
+
+        let extensions = response.extensions.expect("expected permessage-deflate to be negotiated");
+        assert!(extensions.client_no_context_takeover);
+        assert!(!extensions.server_no_context_takeover);
+        assert_eq!(extensions.server_max_window_bits, Some(10));
+
+        assert!(response.response.contains(
+            "Sec-WebSocket-Extensions: permessage-deflate; client_no_context_takeover; server_max_window_bits=10\r\n"
+        ));
+    }
+}